@@ -1,29 +1,55 @@
 use anyhow::{Context, Result};
 use model::{
+    game_event::GameEvent,
     hydra::{
         hydra_message::{HydraData, HydraEventMessage},
         state::HydraNodesState,
     },
-    node::Node,
+    node::{Node, NodeUpdate},
 };
 use rocket::http::Method;
+use rocket::Shutdown;
 use rocket_cors::{AllowedOrigins, CorsOptions};
 use routes::global::global;
 use routes::head::head;
 use routes::heads::heads;
 use routes::new_game::new_game;
+use routes::subscribe::subscribe;
 use serde::Deserialize;
+use sinks::SinkConfig;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::{
-    spawn,
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    signal::unix::{signal, SignalKind},
+    sync::{
+        broadcast,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Mutex as TokioMutex,
+    },
+    task::JoinSet,
+    time::timeout,
 };
+use tokio_util::sync::CancellationToken;
 
 #[macro_use]
 extern crate rocket;
 
 mod model;
 mod routes;
+mod sinks;
+
+/// Capacity of the game-event and node-update broadcast channels. A sink that
+/// falls this far behind the state-update loop skips the events it missed
+/// rather than stalling it; a `/subscribe` client that does is disconnected.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// How long to wait for a head-close transaction to be confirmed before
+/// giving up on that node during shutdown.
+const HEAD_CLOSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the idle reaper checks nodes for a `config.ttl_minutes` timeout.
+const IDLE_REAP_INTERVAL: Duration = Duration::from_secs(60);
 
 // this is a temporary way to store the script address
 pub const SCRIPT_ADDRESS: &str = "addr_test1wp3z9emuaqukk57zsrcnhx0fv2pp9n73cyq7s32mutwklfqjp53s0";
@@ -31,6 +57,8 @@ pub const SCRIPT_CBOR: &str = "5905490100003232323232323223232323232323232232253
 struct MyState {
     state: HydraNodesState,
     config: Config,
+    shutdown: CancellationToken,
+    node_updates: broadcast::Sender<NodeUpdate>,
 }
 
 #[allow(dead_code)]
@@ -38,16 +66,18 @@ struct MyState {
 struct Config {
     ttl_minutes: u64,
     nodes: Vec<NodeConfig>,
+    #[serde(default)]
+    sinks: Vec<SinkConfig>,
 }
 
 #[derive(Debug, Deserialize)]
-struct NodeConfig {
+pub(crate) struct NodeConfig {
     #[serde(default = "localhost")]
-    local_url: String,
-    max_players: usize,
+    pub(crate) local_url: String,
+    pub(crate) max_players: usize,
     remote_url: Option<String>,
     admin_key_file: PathBuf,
-    persisted: bool,
+    pub(crate) persisted: bool,
 }
 
 fn localhost() -> String {
@@ -63,20 +93,72 @@ async fn main() -> Result<()> {
     let (tx, rx): (UnboundedSender<HydraData>, UnboundedReceiver<HydraData>) =
         mpsc::unbounded_channel();
 
+    let shutdown_token = CancellationToken::new();
+    // Separate from `shutdown_token`: cancelled only once `close_all_heads`
+    // has had its chance to await close confirmations, so a node's socket
+    // reader and the state-update loop that processes its `HeadIsClosed`
+    // message don't get torn down out from under a close still in flight.
+    let connections_shutdown = CancellationToken::new();
+    let background_tasks = Arc::new(TokioMutex::new(JoinSet::new()));
+
     let mut nodes = vec![];
-    for node in &config.nodes {
-        let node = Node::try_new(&node, &tx)
-            .await
-            .context("failed to construct new node")?;
+    for node_config in &config.nodes {
+        let node = Node::try_new(
+            node_config,
+            &tx,
+            connections_shutdown.clone(),
+            background_tasks.clone(),
+        )
+        .await
+        .context("failed to construct new node")?;
         nodes.push(node);
     }
 
     let hydra_state = HydraNodesState::from_nodes(nodes);
 
-    let hydra_state_clone = hydra_state.clone();
-    spawn(async move {
-        update(hydra_state_clone, rx).await;
-    });
+    let (events_tx, _events_rx) = broadcast::channel::<GameEvent>(EVENT_CHANNEL_CAPACITY);
+    let sink_handles = sinks::spawn_all(&config.sinks, &events_tx);
+
+    let (node_updates_tx, _node_updates_rx) =
+        broadcast::channel::<NodeUpdate>(EVENT_CHANNEL_CAPACITY);
+
+    {
+        let mut background_tasks = background_tasks.lock().await;
+
+        for handle in sink_handles {
+            background_tasks.spawn(async move {
+                let _ = handle.await;
+            });
+        }
+
+        let hydra_state_clone = hydra_state.clone();
+        let update_events_tx = events_tx.clone();
+        let update_node_updates_tx = node_updates_tx.clone();
+        let update_shutdown = connections_shutdown.clone();
+        background_tasks.spawn(async move {
+            update(
+                hydra_state_clone,
+                rx,
+                update_events_tx,
+                update_node_updates_tx,
+                update_shutdown,
+            )
+            .await;
+        });
+
+        let reap_state = hydra_state.clone();
+        let reap_ttl = Duration::from_secs(config.ttl_minutes * 60);
+        let reap_shutdown = shutdown_token.clone();
+        background_tasks.spawn(reap_idle_nodes(reap_state, reap_ttl, reap_shutdown));
+    }
+
+    // The update task holds its own clone (`update_events_tx`) for as long
+    // as it runs; this one must still be dropped so the channel actually
+    // closes once that clone goes away. Sink tasks only stop waiting on
+    // `rx.recv()` once every sender is dropped, and this variable would
+    // otherwise sit alive in `main`'s frame through the shutdown drain
+    // below, keeping the channel open and that drain hanging forever.
+    drop(events_tx);
 
     let cors = CorsOptions::default()
         .allowed_origins(AllowedOrigins::all())
@@ -88,22 +170,177 @@ async fn main() -> Result<()> {
         )
         .allow_credentials(true);
 
-    let _rocket = rocket::build()
+    let ignited = rocket::build()
         .manage(MyState {
-            state: hydra_state,
+            state: hydra_state.clone(),
             config,
+            shutdown: shutdown_token.clone(),
+            node_updates: node_updates_tx,
         })
-        .mount("/", routes![new_game, heads, head, global])
+        .mount("/", routes![new_game, heads, head, global, subscribe])
         .attach(cors.to_cors().unwrap())
-        .launch()
+        .ignite()
         .await?;
 
+    let rocket_shutdown = ignited.shutdown();
+    background_tasks
+        .lock()
+        .await
+        .spawn(listen_for_shutdown_signals(
+            shutdown_token.clone(),
+            rocket_shutdown,
+        ));
+
+    let result = ignited.launch().await;
+
+    shutdown_token.cancel();
+    close_all_heads(&hydra_state).await;
+    connections_shutdown.cancel();
+
+    let mut background_tasks = background_tasks.lock().await;
+    while background_tasks.join_next().await.is_some() {}
+
+    result?;
     Ok(())
 }
 
-async fn update(state: HydraNodesState, mut rx: UnboundedReceiver<HydraData>) {
+/// Waits for SIGINT, SIGTERM or SIGHUP and, on the first one received,
+/// cancels `shutdown` and asks Rocket to stop serving new connections.
+async fn listen_for_shutdown_signals(shutdown: CancellationToken, rocket_shutdown: Shutdown) {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("failed to install SIGTERM handler: {:?}", e);
+            return;
+        }
+    };
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("failed to install SIGHUP handler: {:?}", e);
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("received SIGTERM, shutting down"),
+        _ = sighup.recv() => info!("received SIGHUP, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("received SIGINT, shutting down"),
+    }
+
+    shutdown.cancel();
+    rocket_shutdown.notify();
+}
+
+/// Closes every open head so a deploy/restart doesn't abandon it: submits a
+/// close transaction for each node that still has a `head_id` and waits up
+/// to `HEAD_CLOSE_TIMEOUT` for it to be confirmed.
+///
+/// Clones the affected nodes out from under `state`'s lock before awaiting
+/// any of them, rather than holding it for the whole loop: `update()` needs
+/// that same write lock to process the `HeadIsClosed` message and call
+/// `mark_closed`, so holding it here would make every close time out
+/// waiting on a confirmation that could never arrive.
+async fn close_all_heads(state: &HydraNodesState) {
+    let to_close: Vec<Node> = {
+        let state_guard = state.state.read().await;
+        state_guard
+            .nodes
+            .iter()
+            .filter(|node| node.head_id.read().unwrap().is_some())
+            .cloned()
+            .collect()
+    };
+
+    for node in &to_close {
+        let head_id = node.head_id.read().unwrap().clone();
+
+        match timeout(HEAD_CLOSE_TIMEOUT, node.close_head()).await {
+            Ok(Ok(())) => info!("closed head {:?}", head_id),
+            Ok(Err(e)) => warn!("failed to close head {:?}: {:?}", head_id, e),
+            Err(_) => warn!("timed out waiting to close head {:?}", head_id),
+        }
+    }
+}
+
+/// Periodically reclaims non-persisted nodes whose head has sat idle past
+/// `ttl`, so matchmaking doesn't run out of heads to hand out because a
+/// past game's players simply never came back. Persisted nodes are never
+/// reaped: they're intentionally dedicated to one head.
+///
+/// Collects idle nodes under a short-lived read lock, then closes and
+/// awaits confirmation for each one without holding it, only taking a
+/// write lock again afterwards to apply `reset_for_matchmaking` to the
+/// node a confirmed close belongs to. Holding the write lock across the
+/// close-confirmation wait would starve `update()` of the same lock it
+/// needs to process the `HeadIsClosed` message that confirmation depends
+/// on, so a reap could never actually succeed.
+async fn reap_idle_nodes(state: HydraNodesState, ttl: Duration, shutdown: CancellationToken) {
+    let mut interval = tokio::time::interval(IDLE_REAP_INTERVAL);
+
     loop {
-        match rx.recv().await {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+
+        let idle: Vec<Node> = {
+            let state_guard = state.state.read().await;
+            state_guard
+                .nodes
+                .iter()
+                .filter(|node| {
+                    !node.stats.persisted
+                        && node.is_idle(ttl)
+                        && node.head_id.read().unwrap().is_some()
+                })
+                .cloned()
+                .collect()
+        };
+
+        for node in &idle {
+            let head_id = node.head_id.read().unwrap().clone();
+
+            match timeout(HEAD_CLOSE_TIMEOUT, node.close_head()).await {
+                Ok(Ok(())) => {
+                    info!("reaped idle head {:?}", head_id);
+                    let authority = node.connection_info.to_authority();
+                    let mut state_guard = state.state.write().await;
+                    if let Some(actual) = state_guard
+                        .nodes
+                        .iter_mut()
+                        .find(|n| n.connection_info.to_authority() == authority)
+                    {
+                        actual.reset_for_matchmaking();
+                    }
+                }
+                Ok(Err(e)) => warn!("failed to close idle head {:?}: {:?}", head_id, e),
+                Err(_) => warn!("timed out waiting to close idle head {:?}", head_id),
+            }
+        }
+    }
+}
+
+async fn update(
+    state: HydraNodesState,
+    mut rx: UnboundedReceiver<HydraData>,
+    events: broadcast::Sender<GameEvent>,
+    node_updates: broadcast::Sender<NodeUpdate>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let received = tokio::select! {
+            received = rx.recv() => received,
+            _ = shutdown.cancelled() => {
+                info!("update loop shutting down, draining pending messages");
+                while let Ok(message) = rx.try_recv() {
+                    drop(message);
+                }
+                break;
+            }
+        };
+
+        match received {
             Some(HydraData::Received { message, authority }) => {
                 let mut state_guard = state.state.write().await;
                 let nodes = &mut state_guard.nodes;
@@ -115,25 +352,70 @@ async fn update(state: HydraNodesState, mut rx: UnboundedReceiver<HydraData>) {
                     continue;
                 }
                 let node = node.unwrap();
+                // Any message from hydra about this node counts as activity,
+                // so an actively-playing game is never mistaken for an idle
+                // one by `reap_idle_nodes`.
+                node.touch();
                 match message {
-                    HydraEventMessage::HeadIsOpen(head_is_open) if node.head_id.is_none() => {
+                    HydraEventMessage::HeadIsOpen(head_is_open)
+                        if node.head_id.read().unwrap().is_none() =>
+                    {
                         info!(
                             "updating node {:?} with head_id {:?}",
                             node.local_connection.to_authority(),
                             head_is_open.head_id
                         );
-                        node.head_id = Some(head_is_open.head_id.to_string());
+                        *node.head_id.write().unwrap() = Some(head_is_open.head_id.to_string());
+                        emit(
+                            &events,
+                            GameEvent::HeadOpened {
+                                head_id: head_is_open.head_id.to_string(),
+                            },
+                        );
+                        publish_node_update(&node_updates, node);
                     }
-                    HydraEventMessage::SnapshotConfirmed(snapshot_confirmed) => node
-                        .stats
-                        .calculate_stats(snapshot_confirmed.confirmed_transactions),
-
-                    HydraEventMessage::TxValid(tx) => match node.add_transaction(tx) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            warn!("failed to add transaction {:?}", e);
+                    HydraEventMessage::HeadIsClosed(_) => {
+                        info!(
+                            "node {:?} confirmed head close",
+                            node.connection_info.to_authority()
+                        );
+                        node.mark_closed();
+                        publish_node_update(&node_updates, node);
+                    }
+                    HydraEventMessage::SnapshotConfirmed(snapshot_confirmed) => {
+                        let tx_count = snapshot_confirmed.confirmed_transactions.len();
+                        let deltas = node
+                            .stats
+                            .calculate_stats(snapshot_confirmed.confirmed_transactions);
+                        let bytes = deltas.iter().map(|delta| delta.update.bytes).sum();
+                        emit(&events, GameEvent::SnapshotConfirmed { tx_count, bytes });
+                        for delta in deltas {
+                            emit(
+                                &events,
+                                GameEvent::PlayerStateDelta {
+                                    pkh: hex::encode(delta.pkh),
+                                    kills: delta.update.kills,
+                                    items: delta.update.items,
+                                    secrets: delta.update.secrets,
+                                    play_time: delta.update.play_time,
+                                },
+                            );
                         }
-                    },
+                        publish_node_update(&node_updates, node);
+                    }
+
+                    HydraEventMessage::TxValid(tx) => {
+                        let tx_id = hex::encode(&tx.tx_id);
+                        match node.add_transaction(tx) {
+                            Ok(_) => {
+                                emit(&events, GameEvent::TxValid { tx_id });
+                                publish_node_update(&node_updates, node);
+                            }
+                            Err(e) => {
+                                warn!("failed to add transaction {:?}", e);
+                            }
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -145,3 +427,15 @@ async fn update(state: HydraNodesState, mut rx: UnboundedReceiver<HydraData>) {
         }
     }
 }
+
+/// Publishes a `GameEvent` to every subscribed sink. Sending is
+/// fire-and-forget: a `SendError` just means there are currently no
+/// subscribers, which is fine.
+fn emit(events: &broadcast::Sender<GameEvent>, event: GameEvent) {
+    let _ = events.send(event);
+}
+
+/// Publishes a fresh `NodeUpdate` snapshot to every `/subscribe` client.
+fn publish_node_update(node_updates: &broadcast::Sender<NodeUpdate>, node: &Node) {
+    let _ = node_updates.send(NodeUpdate::from(node));
+}
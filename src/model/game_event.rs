@@ -0,0 +1,29 @@
+use serde::Serialize;
+
+/// Derived game activity emitted by the state-update loop as it processes
+/// `HydraData::Received` messages: one event when a node's head opens, one
+/// per confirmed snapshot (followed by one `PlayerStateDelta` per player
+/// update it confirms), and one per accepted valid transaction. Other
+/// messages emit nothing. Sinks subscribe to a broadcast of these instead of
+/// polling the REST endpoints.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum GameEvent {
+    HeadOpened {
+        head_id: String,
+    },
+    SnapshotConfirmed {
+        tx_count: usize,
+        bytes: u64,
+    },
+    PlayerStateDelta {
+        pkh: String,
+        kills: u64,
+        items: u64,
+        secrets: u64,
+        play_time: u64,
+    },
+    TxValid {
+        tx_id: String,
+    },
+}
@@ -0,0 +1,6 @@
+pub mod game_event;
+pub mod game_state;
+pub mod hydra;
+pub mod node;
+pub mod player;
+pub mod tx_builder;
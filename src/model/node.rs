@@ -17,9 +17,13 @@ use pallas::{
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use serde_json::Value;
 use std::collections::HashMap;
-use tokio::sync::mpsc::UnboundedSender;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc::UnboundedSender, Mutex as TokioMutex, Notify};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 
-use crate::{model::hydra::utxo::UTxO, SCRIPT_ADDRESS};
+use crate::{model::hydra::utxo::UTxO, NodeConfig, SCRIPT_ADDRESS};
 
 use hex::FromHex;
 
@@ -34,14 +38,32 @@ use super::{
     tx_builder::TxBuilder,
 };
 
+/// Minimum and maximum delay between reconnect attempts. The actual delay
+/// also gets a small amount of jitter so that many nodes losing their
+/// connection at once don't all hammer the remote at the same instant.
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How long `close_head` waits for hydra to confirm a close transaction
+/// actually landed before giving up. Mirrors `main::HEAD_CLOSE_TIMEOUT`,
+/// which bounds the same call from the caller's side.
+const CLOSE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub struct Node {
     pub connection_info: ConnectionInfo,
-    pub head_id: Option<String>,
-    pub socket: HydraSocket,
+    pub head_id: Arc<RwLock<Option<String>>>,
+    pub socket: Arc<RwLock<HydraSocket>>,
+    pub connection_state: Arc<RwLock<ConnectionState>>,
     pub players: Vec<Player>,
     pub stats: NodeStats,
-    pub tx_builder: TxBuilder,
+    pub tx_builder: Arc<RwLock<TxBuilder>>,
+    pub max_players: usize,
+    last_activity: Arc<RwLock<Instant>>,
+    close_confirmed: Arc<Notify>,
+    shutdown: CancellationToken,
+    background: Arc<TokioMutex<JoinSet<()>>>,
+    writer: UnboundedSender<HydraData>,
 }
 
 #[derive(Clone)]
@@ -52,6 +74,66 @@ pub struct ConnectionInfo {
 }
 pub struct NodeSummary(pub Node);
 
+/// A point-in-time snapshot of a node's stats, broadcast to `/subscribe`
+/// clients on connect and every time the state-update loop processes a
+/// message that changes this node.
+#[derive(Clone, serde::Serialize)]
+pub struct NodeUpdate {
+    pub head_id: Option<String>,
+    pub active_games: usize,
+    pub transactions: u64,
+    pub bytes: u64,
+    pub kills: u64,
+    pub items: u64,
+    pub secrets: u64,
+    pub play_time: u64,
+}
+
+impl From<&Node> for NodeUpdate {
+    fn from(node: &Node) -> Self {
+        NodeUpdate {
+            head_id: node.head_id.read().unwrap().clone(),
+            active_games: node.players.len(),
+            transactions: node.stats.transactions,
+            bytes: node.stats.bytes,
+            kills: node.stats.kills,
+            items: node.stats.items,
+            secrets: node.stats.secrets,
+            play_time: node.stats.play_time,
+        }
+    }
+}
+
+/// Lifecycle of a node's websocket connection to its Hydra node, surfaced in
+/// `NodeSummary` so operators can see unhealthy nodes without tailing logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Open,
+    Reconnecting,
+    Closed,
+}
+
+impl ConnectionState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Open => "open",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Closed => "closed",
+        }
+    }
+}
+
+impl Serialize for ConnectionState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 #[derive(Clone)]
 pub struct NodeStats {
     pub persisted: bool,
@@ -62,6 +144,14 @@ pub struct NodeStats {
     pub secrets: u64,
     pub play_time: u64,
     pub pending_transactions: HashMap<Vec<u8>, StateUpdate>,
+    pub pending_players: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+/// A single player's state change, confirmed as part of a snapshot. Emitted
+/// as a `GameEvent::PlayerStateDelta` by the caller of `calculate_stats`.
+pub struct PlayerStateUpdate {
+    pub pkh: Vec<u8>,
+    pub update: StateUpdate,
 }
 
 #[derive(Clone)]
@@ -81,43 +171,67 @@ pub enum NetworkRequestError {
 
 impl Node {
     pub async fn try_new(
-        uri: &str,
+        config: &NodeConfig,
         writer: &UnboundedSender<HydraData>,
-        persisted: bool,
+        shutdown: CancellationToken,
+        background: Arc<TokioMutex<JoinSet<()>>>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let connection_info: ConnectionInfo = uri.to_string().try_into()?;
+        let connection_info: ConnectionInfo = config.local_url.clone().try_into()?;
 
         let socket = HydraSocket::new(connection_info.to_websocket_url().as_str(), writer).await?;
         let mut node = Node {
             connection_info,
-            head_id: None,
+            head_id: Arc::new(RwLock::new(None)),
+            socket: Arc::new(RwLock::new(socket)),
+            connection_state: Arc::new(RwLock::new(ConnectionState::Connecting)),
             players: Vec::new(),
-            socket,
-            stats: NodeStats::new(persisted),
-            tx_builder: TxBuilder::new(
+            stats: NodeStats::new(config.persisted),
+            tx_builder: Arc::new(RwLock::new(TxBuilder::new(
                 <[u8; 32]>::from_hex(
                     "AF9292ADA4AA01DB918BBBA7796ACF235E6D87D3EBC0D93FA44AA7E0531CF226",
                 )
                 .unwrap(),
-            ),
+            ))),
+            max_players: config.max_players,
+            last_activity: Arc::new(RwLock::new(Instant::now())),
+            close_confirmed: Arc::new(Notify::new()),
+            shutdown,
+            background,
+            writer: writer.clone(),
         };
 
-        node.listen();
-        let utxos = node
+        node.bootstrap().await?;
+        node.listen().await;
+
+        Ok(node)
+    }
+
+    /// Fetches the current UTxO set and re-derives the script reference,
+    /// then marks the connection open. Runs once when the node is first
+    /// constructed; after a reconnect, `listen` does the same work through
+    /// `bootstrap_after_reconnect`.
+    async fn bootstrap(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let utxos = self
             .fetch_utxos()
             .await
             .map_err(|_| "Failed to fetch UTxOs")?;
-        let maybe_script_ref = TxBuilder::find_script_ref(utxos);
-        match maybe_script_ref {
+
+        match TxBuilder::find_script_ref(utxos) {
             Some(script_ref) => {
-                let _ = node.tx_builder.set_script_ref(&script_ref);
+                let _ = self.tx_builder.write().unwrap().set_script_ref(&script_ref);
                 println!("Set script ref! {:?}", script_ref);
             }
             None => {
                 println!("No script ref found for this node.");
             }
         }
-        Ok(node)
+
+        // The head may have changed while we were disconnected, so forget
+        // what we knew and let the next `HeadIsOpen` message repopulate it.
+        *self.head_id.write().unwrap() = None;
+        *self.connection_state.write().unwrap() = ConnectionState::Open;
+
+        Ok(())
     }
 
     pub async fn add_player(&mut self, player: Player) -> Result<(), Box<dyn std::error::Error>> {
@@ -126,47 +240,188 @@ impl Node {
             .await
             .map_err(|_| "Failed to fetch utxos")?;
 
-        let new_game_tx = self.tx_builder.build_new_game_state(&player, utxos)?;
+        let new_game_tx = self
+            .tx_builder
+            .read()
+            .unwrap()
+            .build_new_game_state(&player, utxos)?;
 
         let message: String = NewTx::new(new_game_tx)?.into();
 
         self.players.push(player);
-        self.send(message);
+        self.send(message).await;
+        self.touch();
 
         Ok(())
     }
 
-    pub fn listen(&self) {
-        let receiver = self.socket.receiver.clone();
-        let identifier = self.connection_info.to_authority();
-        tokio::spawn(async move { receiver.lock().await.listen(identifier.as_str()).await });
+    /// Records that this node just did something matchmaking-relevant, so
+    /// the idle reaper doesn't reclaim it out from under an active game.
+    pub fn touch(&self) {
+        *self.last_activity.write().unwrap() = Instant::now();
     }
 
-    pub fn send(&self, message: String) {
-        let sender = self.socket.sender.clone();
-        tokio::spawn(async move {
-            let _ = sender.lock().await.send(HydraData::Send(message)).await;
-        });
+    /// True once this node has gone `ttl` without `touch`. Persisted nodes
+    /// are exempt from the reaper, so this is only meaningful for
+    /// matchmaking-managed ones.
+    pub fn is_idle(&self, ttl: Duration) -> bool {
+        self.last_activity.read().unwrap().elapsed() >= ttl
     }
 
-    pub async fn fetch_utxos(&self) -> Result<Vec<UTxO>, NetworkRequestError> {
-        let request_url = self.connection_info.to_http_url() + "/snapshot/utxo";
-        let response = reqwest::get(&request_url)
+    /// Clears this node's players and head so it can be handed back to
+    /// matchmaking once its head is closed. Does not touch the connection
+    /// itself, which `listen`'s reconnect loop manages independently.
+    pub fn reset_for_matchmaking(&mut self) {
+        self.players.clear();
+        *self.head_id.write().unwrap() = None;
+        self.touch();
+    }
+
+    /// Wakes up anyone in `close_head` waiting on this node's close
+    /// transaction to be confirmed. Called from the state-update loop once
+    /// it sees the corresponding `HeadIsClosed` message.
+    pub fn mark_closed(&self) {
+        self.close_confirmed.notify_one();
+    }
+
+    /// Submits a close transaction for this node's open head and waits up to
+    /// `CLOSE_CONFIRM_TIMEOUT` for `mark_closed` to be called back once the
+    /// state-update loop sees the close actually confirmed on-chain, rather
+    /// than returning as soon as the transaction is merely submitted. Used
+    /// during graceful shutdown and idle reaping, so neither abandons or
+    /// recycles a head whose close hasn't really landed yet.
+    ///
+    /// Takes `&self` rather than `&mut self` on purpose: every field it
+    /// touches is shared (`Arc<RwLock<_>>`), so callers can clone the `Node`
+    /// out from under a global lock, await confirmation here without
+    /// holding it, and only take a lock again to apply the (non-shared)
+    /// effects of a confirmed close, such as `reset_for_matchmaking`.
+    pub async fn close_head(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let head_id = self
+            .head_id
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or("node has no open head to close")?;
+
+        let utxos = self
+            .fetch_utxos()
             .await
-            .map_err(NetworkRequestError::HttpError)?;
+            .map_err(|_| "Failed to fetch utxos")?;
 
-        let body = response
-            .json::<HashMap<String, Value>>()
+        let close_tx = self
+            .tx_builder
+            .read()
+            .unwrap()
+            .build_close_head(&head_id, utxos)?;
+        let message: String = NewTx::new(close_tx)?.into();
+
+        self.send(message).await;
+
+        tokio::time::timeout(CLOSE_CONFIRM_TIMEOUT, self.close_confirmed.notified())
             .await
-            .map_err(NetworkRequestError::HttpError)?;
+            .map_err(|_| "timed out waiting for head close confirmation")?;
+
+        Ok(())
+    }
 
-        let utxos = body
-            .iter()
-            .map(|(key, value)| UTxO::try_from_value(key, value))
-            .map(|result| result.map_err(|e| NetworkRequestError::DeserializationError(e)))
-            .collect::<Result<Vec<UTxO>, NetworkRequestError>>()?;
+    /// Spawns the supervised connection loop: listens on the current socket
+    /// until it drops, then reconnects with capped exponential backoff and
+    /// re-runs the bootstrap that `try_new` does today. Tracked in
+    /// `self.background` rather than a bare `tokio::spawn` so shutdown can
+    /// await it, and races every wait against `self.shutdown` so it actually
+    /// stops instead of reconnecting forever.
+    pub async fn listen(&self) {
+        let connection_info = self.connection_info.clone();
+        let socket = self.socket.clone();
+        let connection_state = self.connection_state.clone();
+        let head_id = self.head_id.clone();
+        let tx_builder = self.tx_builder.clone();
+        let writer = self.writer.clone();
+        let shutdown = self.shutdown.clone();
+
+        self.background.lock().await.spawn(async move {
+            let identifier = connection_info.to_authority();
+            let mut attempt: u32 = 0;
+
+            loop {
+                let receiver = socket.read().unwrap().receiver.clone();
+                tokio::select! {
+                    _ = receiver.lock().await.listen(identifier.as_str()) => {}
+                    _ = shutdown.cancelled() => {
+                        eprintln!(
+                            "node {:?} shutting down, stopping reconnect supervisor",
+                            identifier
+                        );
+                        return;
+                    }
+                }
 
-        Ok(utxos)
+                *connection_state.write().unwrap() = ConnectionState::Reconnecting;
+                eprintln!("node {:?} disconnected, reconnecting", identifier);
+
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(reconnect_delay(attempt)) => {}
+                        _ = shutdown.cancelled() => {
+                            eprintln!(
+                                "node {:?} shutting down, stopping reconnect supervisor",
+                                identifier
+                            );
+                            return;
+                        }
+                    }
+
+                    match HydraSocket::new(connection_info.to_websocket_url().as_str(), &writer)
+                        .await
+                    {
+                        Ok(new_socket) => {
+                            *socket.write().unwrap() = new_socket;
+
+                            match bootstrap_after_reconnect(&connection_info, &tx_builder).await {
+                                Ok(()) => {
+                                    *head_id.write().unwrap() = None;
+                                    *connection_state.write().unwrap() = ConnectionState::Open;
+                                    eprintln!("node {:?} reconnected", identifier);
+                                    attempt = 0;
+                                }
+                                Err(e) => {
+                                    eprintln!(
+                                        "node {:?} reconnected but failed to bootstrap: {:?}",
+                                        identifier, e
+                                    );
+                                    attempt = attempt.saturating_add(1);
+                                }
+                            }
+
+                            break;
+                        }
+                        Err(e) => {
+                            eprintln!(
+                                "node {:?} reconnect attempt {} failed: {:?}",
+                                identifier, attempt, e
+                            );
+                            attempt = attempt.saturating_add(1);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Sends `message` over the socket from a task tracked in
+    /// `self.background` instead of a bare `tokio::spawn`, so shutdown can
+    /// await it draining rather than leaving it to run unsupervised.
+    pub async fn send(&self, message: String) {
+        let socket = self.socket.clone();
+        self.background.lock().await.spawn(async move {
+            let sender = socket.read().unwrap().sender.clone();
+            let _ = sender.lock().await.send(HydraData::Send(message)).await;
+        });
+    }
+
+    pub async fn fetch_utxos(&self) -> Result<Vec<UTxO>, NetworkRequestError> {
+        fetch_utxos(&self.connection_info).await
     }
 
     pub fn add_transaction(
@@ -238,9 +493,13 @@ impl Node {
                     None => return Err("No player found".into()),
                 };
 
+                let pkh = player.pkh.clone();
                 let state_update =
                     player.generate_state_update(transaction.cbor.len() as u64, game_state);
 
+                self.stats
+                    .pending_players
+                    .insert(transaction.tx_id.clone(), pkh);
                 self.stats
                     .pending_transactions
                     .insert(transaction.tx_id, state_update);
@@ -252,16 +511,64 @@ impl Node {
     }
 }
 
+/// Delay before the next reconnect attempt: exponential from
+/// `RECONNECT_MIN_DELAY` up to `RECONNECT_MAX_DELAY`, with up to 20% jitter
+/// so simultaneous disconnects don't retry in lockstep.
+fn reconnect_delay(attempt: u32) -> Duration {
+    let base = (RECONNECT_MIN_DELAY.as_secs_f64() * 2f64.powi(attempt as i32))
+        .min(RECONNECT_MAX_DELAY.as_secs_f64());
+    let jitter = rand::random::<f64>() * 0.2 * base;
+    Duration::from_secs_f64(base + jitter)
+}
+
+/// Re-fetches the UTxO set and re-derives the script reference for a node
+/// whose socket just reconnected, mirroring the bootstrap in `Node::try_new`.
+async fn bootstrap_after_reconnect(
+    connection_info: &ConnectionInfo,
+    tx_builder: &Arc<RwLock<TxBuilder>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let utxos = fetch_utxos(connection_info)
+        .await
+        .map_err(|_| "Failed to fetch UTxOs")?;
+
+    if let Some(script_ref) = TxBuilder::find_script_ref(utxos) {
+        let _ = tx_builder.write().unwrap().set_script_ref(&script_ref);
+    }
+
+    Ok(())
+}
+
+async fn fetch_utxos(connection_info: &ConnectionInfo) -> Result<Vec<UTxO>, NetworkRequestError> {
+    let request_url = connection_info.to_http_url() + "/snapshot/utxo";
+    let response = reqwest::get(&request_url)
+        .await
+        .map_err(NetworkRequestError::HttpError)?;
+
+    let body = response
+        .json::<HashMap<String, Value>>()
+        .await
+        .map_err(NetworkRequestError::HttpError)?;
+
+    let utxos = body
+        .iter()
+        .map(|(key, value)| UTxO::try_from_value(key, value))
+        .map(|result| result.map_err(|e| NetworkRequestError::DeserializationError(e)))
+        .collect::<Result<Vec<UTxO>, NetworkRequestError>>()?;
+
+    Ok(utxos)
+}
+
 impl Serialize for Node {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("Node", 4)?;
-        s.serialize_field("id", &self.head_id)?;
+        let mut s = serializer.serialize_struct("Node", 5)?;
+        s.serialize_field("id", &*self.head_id.read().unwrap())?;
         s.serialize_field("total", &self.stats)?;
         // TODO: Make the active games count match the openapi schema
         s.serialize_field("active_games", &self.players.len())?;
+        s.serialize_field("connection_state", &*self.connection_state.read().unwrap())?;
         s.skip_field("socket")?;
         s.skip_field("ephemeral")?;
         s.skip_field("connection_info")?;
@@ -332,13 +639,24 @@ impl NodeStats {
             secrets: 0,
             play_time: 0,
             pending_transactions: HashMap::new(),
+            pending_players: HashMap::new(),
         }
     }
 
-    pub fn calculate_stats(&mut self, confirmed_txs: Vec<Vec<u8>>) {
+    pub fn calculate_stats(&mut self, confirmed_txs: Vec<Vec<u8>>) -> Vec<PlayerStateUpdate> {
+        let mut deltas = Vec::new();
         for tx_id in confirmed_txs {
+            let pkh = self.pending_players.remove(&tx_id);
             match self.pending_transactions.remove(&tx_id) {
-                Some(state_change) => self.update_stats(state_change),
+                Some(state_change) => {
+                    self.update_stats(state_change.clone());
+                    if let Some(pkh) = pkh {
+                        deltas.push(PlayerStateUpdate {
+                            pkh,
+                            update: state_change,
+                        });
+                    }
+                }
 
                 None => println!(
                     "Transaction in snapshot not found in stored transactions: {:?}",
@@ -346,6 +664,7 @@ impl NodeStats {
                 ),
             }
         }
+        deltas
     }
 
     fn update_stats(&mut self, state_change: StateUpdate) {
@@ -361,6 +680,9 @@ impl NodeStats {
         let mut pending_transactions = self.pending_transactions.clone();
         pending_transactions.extend(other.pending_transactions);
 
+        let mut pending_players = self.pending_players.clone();
+        pending_players.extend(other.pending_players);
+
         NodeStats {
             persisted: self.persisted && other.persisted,
             transactions: self.transactions + other.transactions,
@@ -370,6 +692,7 @@ impl NodeStats {
             secrets: self.secrets + other.secrets,
             play_time: self.play_time + other.play_time,
             pending_transactions,
+            pending_players,
         }
     }
 }
@@ -396,10 +719,14 @@ impl Serialize for NodeSummary {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("NodeSummary", 3)?;
-        s.serialize_field("id", &self.0.head_id)?;
+        let mut s = serializer.serialize_struct("NodeSummary", 4)?;
+        s.serialize_field("id", &*self.0.head_id.read().unwrap())?;
         s.serialize_field("active_games", &self.0.players.len())?;
         s.serialize_field("persisted", &self.0.stats.persisted)?;
+        s.serialize_field(
+            "connection_state",
+            &*self.0.connection_state.read().unwrap(),
+        )?;
         s.end()
     }
 }
@@ -0,0 +1,5 @@
+pub mod global;
+pub mod head;
+pub mod heads;
+pub mod new_game;
+pub mod subscribe;
@@ -0,0 +1,35 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::model::player::Player;
+use crate::MyState;
+
+/// Assigns `player` to whichever open, non-full head has the fewest players,
+/// `touch`-ing it so the idle reaper leaves it alone while the game is live.
+///
+/// Returns 503 if every head is either closed or already at `max_players` —
+/// there's no queueing here, the client is expected to retry.
+#[post("/new_game", data = "<player>")]
+pub async fn new_game(player: Json<Player>, state: &State<MyState>) -> Result<(), Status> {
+    if state.shutdown.is_cancelled() {
+        return Err(Status::ServiceUnavailable);
+    }
+
+    let mut state_guard = state.state.state.write().await;
+
+    let node = state_guard
+        .nodes
+        .iter_mut()
+        .filter(|node| node.head_id.read().unwrap().is_some())
+        .filter(|node| node.players.len() < node.max_players)
+        .min_by_key(|node| node.players.len())
+        .ok_or(Status::ServiceUnavailable)?;
+
+    node.add_player(player.into_inner())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    node.touch();
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use rocket::State;
+use rocket_ws::{Channel, Message, WebSocket};
+use tokio::sync::broadcast;
+
+use crate::model::node::NodeUpdate;
+use crate::MyState;
+
+/// Streams live `NodeUpdate`s to a client instead of making it poll `/heads`,
+/// `/head` and `/global`. On connect it sends a snapshot of every node's
+/// current stats, then one frame per node-affecting message the
+/// state-update loop processes from then on.
+///
+/// `head_id` narrows the subscription to a single head; omit it to follow
+/// every node. Subscribers that fall behind are dropped rather than
+/// buffered, since a `broadcast` channel only ever holds a bounded backlog.
+#[get("/subscribe?<head_id>")]
+pub fn subscribe(
+    ws: WebSocket,
+    head_id: Option<String>,
+    state: &State<MyState>,
+) -> Channel<'static> {
+    let mut updates = state.node_updates.subscribe();
+    let hydra_state = state.state.clone();
+
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            let snapshot: Vec<NodeUpdate> = {
+                let guard = hydra_state.state.read().await;
+                guard
+                    .nodes
+                    .iter()
+                    .map(NodeUpdate::from)
+                    .filter(|update| matches_filter(update, &head_id))
+                    .collect()
+            };
+
+            for update in snapshot {
+                send_update(&mut stream, &update).await?;
+            }
+
+            loop {
+                match updates.recv().await {
+                    Ok(update) if matches_filter(&update, &head_id) => {
+                        send_update(&mut stream, &update).await?;
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(_)) => break,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            Ok(())
+        })
+    })
+}
+
+async fn send_update(
+    stream: &mut rocket_ws::stream::DuplexStream,
+    update: &NodeUpdate,
+) -> Result<(), rocket_ws::result::Error> {
+    use futures::SinkExt;
+
+    let json = serde_json::to_string(update).unwrap_or_default();
+    stream.send(Message::Text(json)).await
+}
+
+fn matches_filter(update: &NodeUpdate, head_id: &Option<String>) -> bool {
+    match head_id {
+        Some(head_id) => update.head_id.as_deref() == Some(head_id.as_str()),
+        None => true,
+    }
+}
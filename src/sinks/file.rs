@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use super::EventSink;
+use crate::model::game_event::GameEvent;
+
+struct RollingFile {
+    date: String,
+    handle: File,
+}
+
+/// Appends each event as a line of NDJSON to a file under `directory`,
+/// rolling over to a new file every day.
+pub struct FileSink {
+    directory: PathBuf,
+    current: Mutex<Option<RollingFile>>,
+}
+
+impl FileSink {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            current: Mutex::new(None),
+        }
+    }
+
+    async fn open_for_today(&self) -> std::io::Result<RollingFile> {
+        fs::create_dir_all(&self.directory).await?;
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let path = self.directory.join(format!("{}.ndjson", date));
+        let handle = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(RollingFile { date, handle })
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    async fn emit(&self, event: &GameEvent) {
+        let line = match serde_json::to_string(event) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("failed to serialize event for file sink: {:?}", e);
+                return;
+            }
+        };
+
+        let mut current = self.current.lock().await;
+        let today = Utc::now().format("%Y-%m-%d").to_string();
+        let needs_rotation = match current.as_ref() {
+            Some(file) => file.date != today,
+            None => true,
+        };
+
+        if needs_rotation {
+            match self.open_for_today().await {
+                Ok(file) => *current = Some(file),
+                Err(e) => {
+                    warn!("failed to open rolling log file: {:?}", e);
+                    return;
+                }
+            }
+        }
+
+        if let Some(file) = current.as_mut() {
+            if let Err(e) = file
+                .handle
+                .write_all(format!("{}\n", line).as_bytes())
+                .await
+            {
+                warn!("failed to write event to rolling log file: {:?}", e);
+            }
+        }
+    }
+}
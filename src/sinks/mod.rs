@@ -0,0 +1,92 @@
+mod file;
+mod stdout;
+mod webhook;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+
+use crate::model::game_event::GameEvent;
+
+pub use file::FileSink;
+pub use stdout::StdoutSink;
+pub use webhook::WebhookSink;
+
+/// A destination for `GameEvent`s. Each sink runs in its own task reading
+/// off a broadcast channel, so a slow or unreachable sink never blocks the
+/// state-update loop.
+#[async_trait]
+pub trait EventSink: Send + Sync + 'static {
+    async fn emit(&self, event: &GameEvent);
+}
+
+/// Configuration for a single sink, as parsed from `Config.sinks`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SinkConfig {
+    Stdout,
+    Webhook {
+        url: String,
+        #[serde(default = "default_max_retries")]
+        max_retries: u32,
+    },
+    File {
+        #[serde(default = "default_log_dir")]
+        directory: std::path::PathBuf,
+    },
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_log_dir() -> std::path::PathBuf {
+    std::path::PathBuf::from("logs")
+}
+
+impl SinkConfig {
+    fn build(&self) -> Box<dyn EventSink> {
+        match self {
+            SinkConfig::Stdout => Box::new(StdoutSink),
+            SinkConfig::Webhook { url, max_retries } => {
+                Box::new(WebhookSink::new(url.clone(), *max_retries))
+            }
+            SinkConfig::File { directory } => Box::new(FileSink::new(directory.clone())),
+        }
+    }
+}
+
+/// Spawns one task per configured sink, each subscribed to its own receiver
+/// of `events`. Returns the join handles so callers can track them alongside
+/// the rest of the app's async work.
+pub fn spawn_all(
+    configs: &[SinkConfig],
+    events: &broadcast::Sender<GameEvent>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    configs
+        .iter()
+        .map(|config| {
+            let sink = config.build();
+            let mut rx = events.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(event) => sink.emit(&event).await,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("sink lagged behind, skipped {} events", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+/// Exponential backoff with a 30s cap, used by sinks that retry delivery.
+pub(crate) fn backoff_delay(attempt: u32) -> Duration {
+    let secs = (0.5 * 2f64.powi(attempt as i32)).min(30.0);
+    Duration::from_secs_f64(secs)
+}
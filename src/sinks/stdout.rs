@@ -0,0 +1,17 @@
+use async_trait::async_trait;
+
+use super::EventSink;
+use crate::model::game_event::GameEvent;
+
+/// Writes each event as a line of NDJSON to stdout.
+pub struct StdoutSink;
+
+#[async_trait]
+impl EventSink for StdoutSink {
+    async fn emit(&self, event: &GameEvent) {
+        match serde_json::to_string(event) {
+            Ok(line) => println!("{}", line),
+            Err(e) => warn!("failed to serialize event for stdout sink: {:?}", e),
+        }
+    }
+}
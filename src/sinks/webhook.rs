@@ -0,0 +1,55 @@
+use async_trait::async_trait;
+use reqwest::Client;
+
+use super::{backoff_delay, EventSink};
+use crate::model::game_event::GameEvent;
+
+/// POSTs each event as JSON to a configured URL, retrying with capped
+/// exponential backoff before giving up on a single event.
+pub struct WebhookSink {
+    client: Client,
+    url: String,
+    max_retries: u32,
+}
+
+impl WebhookSink {
+    pub fn new(url: String, max_retries: u32) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+            max_retries,
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn emit(&self, event: &GameEvent) {
+        for attempt in 0..=self.max_retries {
+            let result = self.client.post(&self.url).json(event).send().await;
+
+            match result {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => {
+                    warn!(
+                        "webhook sink got non-success status {} (attempt {})",
+                        response.status(),
+                        attempt
+                    );
+                }
+                Err(e) => {
+                    warn!("webhook sink request failed (attempt {}): {:?}", attempt, e);
+                }
+            }
+
+            if attempt < self.max_retries {
+                tokio::time::sleep(backoff_delay(attempt)).await;
+            }
+        }
+
+        warn!(
+            "webhook sink gave up delivering event after {} retries",
+            self.max_retries
+        );
+    }
+}